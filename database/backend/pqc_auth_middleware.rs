@@ -1,71 +1,1468 @@
+mod keystore;
+
 use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
-// In a real scenario, we would import pqcrypto_kyber and pqcrypto_dilithium
-// use pqcrypto_kyber::kyber1024::*;
-// use pqcrypto_dilithium::dilithium5::*;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use pqcrypto_dilithium::dilithium5::{
+    self, DetachedSignature as DilithiumSignature, PublicKey as DilithiumPublicKey,
+    SecretKey as DilithiumSecretKey,
+};
+use pqcrypto_kyber::kyber1024::{self, PublicKey as KyberPublicKey};
+use pqcrypto_traits::kem::{
+    Ciphertext as _, PublicKey as KemPublicKeyTrait, SecretKey as KemSecretKeyTrait,
+    SharedSecret as _,
+};
+use pqcrypto_traits::sign::{
+    DetachedSignature as _, PublicKey as SignPublicKeyTrait, SecretKey as _,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use uuid::Uuid;
+
+/// How long a handshake's derived shared secret stays usable before
+/// `proxy_request` must reject it and force a fresh handshake.
+const SESSION_TTL: Duration = Duration::from_secs(5 * 60);
+
+type SessionId = String;
+
+/// A shared secret derived from a completed handshake, kept around just
+/// long enough for `proxy_request` to use it.
+struct Session {
+    shared_secret: Vec<u8>,
+    created_at: Instant,
+}
 
 struct AppState {
-    // Stores the server's static keypair for Kyber (Key Encapsulation)
-    server_public_key: Vec<u8>,
-    server_secret_key: Vec<u8>,
-}
-
-async function pqc_handshake(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
-    // 1. Client sends their Kyber Public Key + Dilithium Public Key
-    // 2. Server encapsulates a shared secret using Client's Kyber Public Key
-    // 3. Server signs the ciphertext with Server's Dilithium Secret Key
-    // 4. Server returns (Ciphertext, Signature)
-    
-    println!("Initating PQC Handshake...");
-    
-    // PSEUDO-CODE LOGIC
-    /*
-    let client_pk = parse_body(req);
-    let (ciphertext, shared_secret) = encapsulate(&client_pk);
-    let signature = sign(&ciphertext, &data.server_secret_key);
-    
-    return HttpResponse::Ok().json({
-        "ciphertext": ciphertext,
-        "signature": signature
-    });
-    */
-    
-    HttpResponse::Ok().body("PQC Handshake Mock Endpoint")
-}
-
-async function proxy_request(req: HttpRequest) -> impl Responder {
-    // 1. Verify "X-Quantum-Auth" header which contains a JWT signed by Dilithium
-    // 2. If valid, forward request to PostSTGREST or GoTrue
-    
-    let auth_header = req.headers().get("X-Quantum-Auth");
-    match auth_header {
-        Some(_) => {
-            // Verify Logic Here
-            println!("Quantum Signature Verified. Proxying to Postgres...");
-            HttpResponse::Ok().body("Proxy Successful")
-        },
-        None => HttpResponse::Unauthorized().body("Missing Quantum Authorization")
+    // Enabled KEM suites, keyed by catalog name (e.g. "kyber1024"). One
+    // keypair is generated (or loaded from the keystore) per enabled suite
+    // at startup, and `/pqc/rotate` replaces it in place.
+    kems: HashMap<&'static str, Mutex<Rotating<Box<dyn Kem>>>>,
+    // Enabled signature suites, keyed by catalog name (e.g. "dilithium5").
+    sigs: HashMap<&'static str, Mutex<Rotating<Box<dyn Sign>>>>,
+    // Shared secrets derived from completed handshakes, keyed by session id.
+    sessions: Mutex<HashMap<SessionId, Session>>,
+    // Public keys of actors allowed to sign proxied requests, keyed by keyId.
+    known_keys: HashMap<String, KnownKey>,
+    // Where keypairs are persisted across restarts.
+    keystore: keystore::Keystore,
+}
+
+/// How long a retired key stays valid for verification after `/pqc/rotate`
+/// installs its replacement, so in-flight sessions and signatures don't
+/// break the moment a rotation happens.
+const ROTATION_GRACE_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The active keypair for one suite, plus the previous keypair's public key
+/// if it's still inside its post-rotation grace window.
+struct Rotating<T> {
+    active: T,
+    retired_public_key: Option<(Vec<u8>, Instant)>,
+}
+
+impl<T> Rotating<T> {
+    fn new(active: T) -> Self {
+        Rotating { active, retired_public_key: None }
+    }
+
+    /// Installs `new_active` as the active keypair, keeping `retiring_public_key`
+    /// (the old active keypair's public key) valid until the grace period elapses.
+    fn rotate(&mut self, new_active: T, retiring_public_key: Vec<u8>) {
+        self.active = new_active;
+        self.retired_public_key = Some((retiring_public_key, Instant::now() + ROTATION_GRACE_PERIOD));
+    }
+
+    /// The previous public key, if rotation happened recently enough that
+    /// it's still inside its grace window.
+    fn valid_retired_public_key(&self) -> Option<&[u8]> {
+        self.retired_public_key
+            .as_ref()
+            .filter(|(_, expires_at)| Instant::now() < *expires_at)
+            .map(|(pk, _)| pk.as_slice())
+    }
+}
+
+/// Error returned by a `Kem`/`Sign` implementation when it's handed bytes it
+/// can't parse as one of its own keys, ciphertexts, or signatures.
+#[derive(Debug)]
+struct CryptoError;
+
+/// A key-encapsulation suite from the PQC catalog (Kyber, FrodoKEM, NTRU, …).
+/// Each implementor owns its own static keypair so new schemes can be added
+/// to the registry in `main` without touching the handshake handler.
+trait Kem: Send + Sync {
+    /// Catalog name, e.g. for diagnostics; the gateway itself always knows
+    /// which suite it's holding from the `HashMap` key.
+    #[allow(dead_code)]
+    fn name(&self) -> &'static str;
+    fn public_key(&self) -> Vec<u8>;
+    /// Exported so `main` can hand the keypair to the keystore for
+    /// persistence; never sent over the wire.
+    fn secret_key(&self) -> Vec<u8>;
+    /// Encapsulate a fresh shared secret under `recipient_pk`, returning
+    /// `(shared_secret, ciphertext)`.
+    fn encapsulate(&self, recipient_pk: &[u8]) -> Result<(Vec<u8>, Vec<u8>), CryptoError>;
+    /// Recover the shared secret from a ciphertext addressed to this suite's
+    /// own keypair. The gateway is always the encapsulating party today (the
+    /// client decapsulates locally), but the trait models both directions so
+    /// a future client-side or peer-to-peer use of the same suites doesn't
+    /// need a different interface.
+    #[allow(dead_code)]
+    fn decapsulate(&self, ciphertext: &[u8]) -> Result<Vec<u8>, CryptoError>;
+}
+
+/// A signature suite from the PQC catalog (Dilithium, Falcon, SPHINCS+, …).
+trait Sign: Send + Sync {
+    /// Catalog name, e.g. for diagnostics; the gateway itself always knows
+    /// which suite it's holding from the `HashMap` key.
+    #[allow(dead_code)]
+    fn name(&self) -> &'static str;
+    fn public_key(&self) -> Vec<u8>;
+    /// Exported so `main` can hand the keypair to the keystore for
+    /// persistence; never sent over the wire.
+    fn secret_key(&self) -> Vec<u8>;
+    fn sign(&self, message: &[u8]) -> Vec<u8>;
+    /// Verification goes through `SigAlgorithm::verify` instead, since
+    /// `known_keys` resolves a `keyId` to an algorithm before it has a
+    /// suite instance to call this on. Kept on the trait for symmetry with
+    /// `sign` and for suites that only exist as `dyn Sign` (no
+    /// `SigAlgorithm` variant yet).
+    #[allow(dead_code)]
+    fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> bool;
+}
+
+struct Kyber1024Kem {
+    public_key: KyberPublicKey,
+    secret_key: kyber1024::SecretKey,
+}
+
+impl Kyber1024Kem {
+    fn generate() -> Self {
+        let (public_key, secret_key) = kyber1024::keypair();
+        Kyber1024Kem { public_key, secret_key }
+    }
+
+    fn from_bytes(public_key: &[u8], secret_key: &[u8]) -> Result<Self, CryptoError> {
+        Ok(Kyber1024Kem {
+            public_key: KyberPublicKey::from_bytes(public_key).map_err(|_| CryptoError)?,
+            secret_key: kyber1024::SecretKey::from_bytes(secret_key).map_err(|_| CryptoError)?,
+        })
+    }
+}
+
+impl Kem for Kyber1024Kem {
+    fn name(&self) -> &'static str {
+        "kyber1024"
+    }
+
+    fn public_key(&self) -> Vec<u8> {
+        self.public_key.as_bytes().to_vec()
+    }
+
+    fn secret_key(&self) -> Vec<u8> {
+        self.secret_key.as_bytes().to_vec()
+    }
+
+    fn encapsulate(&self, recipient_pk: &[u8]) -> Result<(Vec<u8>, Vec<u8>), CryptoError> {
+        let pk = KyberPublicKey::from_bytes(recipient_pk).map_err(|_| CryptoError)?;
+        let (shared_secret, ciphertext) = kyber1024::encapsulate(&pk);
+        Ok((shared_secret.as_bytes().to_vec(), ciphertext.as_bytes().to_vec()))
+    }
+
+    fn decapsulate(&self, ciphertext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let ct = kyber1024::Ciphertext::from_bytes(ciphertext).map_err(|_| CryptoError)?;
+        let shared_secret = kyber1024::decapsulate(&ct, &self.secret_key);
+        Ok(shared_secret.as_bytes().to_vec())
+    }
+}
+
+struct Dilithium5Sign {
+    public_key: DilithiumPublicKey,
+    secret_key: DilithiumSecretKey,
+}
+
+impl Dilithium5Sign {
+    fn generate() -> Self {
+        let (public_key, secret_key) = dilithium5::keypair();
+        Dilithium5Sign { public_key, secret_key }
+    }
+
+    fn from_bytes(public_key: &[u8], secret_key: &[u8]) -> Result<Self, CryptoError> {
+        Ok(Dilithium5Sign {
+            public_key: DilithiumPublicKey::from_bytes(public_key).map_err(|_| CryptoError)?,
+            secret_key: DilithiumSecretKey::from_bytes(secret_key).map_err(|_| CryptoError)?,
+        })
+    }
+}
+
+impl Sign for Dilithium5Sign {
+    fn name(&self) -> &'static str {
+        "dilithium5"
+    }
+
+    fn public_key(&self) -> Vec<u8> {
+        self.public_key.as_bytes().to_vec()
+    }
+
+    fn secret_key(&self) -> Vec<u8> {
+        self.secret_key.as_bytes().to_vec()
+    }
+
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        dilithium5::detached_sign(message, &self.secret_key)
+            .as_bytes()
+            .to_vec()
+    }
+
+    fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+        let pk = match DilithiumPublicKey::from_bytes(public_key) {
+            Ok(pk) => pk,
+            Err(_) => return false,
+        };
+        let sig = match DilithiumSignature::from_bytes(signature) {
+            Ok(sig) => sig,
+            Err(_) => return false,
+        };
+        dilithium5::verify_detached_signature(&sig, message, &pk).is_ok()
+    }
+}
+
+/// Builds a fresh keypair for `algorithm`, or `None` if it isn't registered.
+/// Used both at startup (first boot) and by `/pqc/rotate`.
+fn generate_kem(algorithm: &str) -> Option<Box<dyn Kem>> {
+    match algorithm {
+        "kyber1024" => Some(Box::new(Kyber1024Kem::generate())),
+        _ => None,
     }
 }
 
+/// Builds a fresh keypair for `algorithm`, or `None` if it isn't registered.
+fn generate_sig(algorithm: &str) -> Option<Box<dyn Sign>> {
+    match algorithm {
+        "dilithium5" => Some(Box::new(Dilithium5Sign::generate())),
+        _ => None,
+    }
+}
+
+/// Loads `algorithm`'s keypair from the keystore, generating (and
+/// persisting) a fresh one on first boot.
+fn load_or_generate_kem(
+    store: &keystore::Keystore,
+    algorithm: &'static str,
+) -> std::io::Result<Box<dyn Kem>> {
+    if let Some(stored) = store.load(algorithm)? {
+        let kem = Kyber1024Kem::from_bytes(&stored.public_key, &stored.secret_key)
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "corrupt keystore entry"))?;
+        return Ok(Box::new(kem));
+    }
+    let kem = generate_kem(algorithm).expect("algorithm is in KEM_PREFERENCE");
+    store.store(algorithm, &kem.public_key(), &kem.secret_key())?;
+    Ok(kem)
+}
+
+/// Loads `algorithm`'s keypair from the keystore, generating (and
+/// persisting) a fresh one on first boot.
+fn load_or_generate_sig(
+    store: &keystore::Keystore,
+    algorithm: &'static str,
+) -> std::io::Result<Box<dyn Sign>> {
+    if let Some(stored) = store.load(algorithm)? {
+        let sig = Dilithium5Sign::from_bytes(&stored.public_key, &stored.secret_key)
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "corrupt keystore entry"))?;
+        return Ok(Box::new(sig));
+    }
+    let sig = generate_sig(algorithm).expect("algorithm is in SIG_PREFERENCE");
+    store.store(algorithm, &sig.public_key(), &sig.secret_key())?;
+    Ok(sig)
+}
+
+/// KEM suites this gateway can negotiate, in order of decreasing preference.
+/// Names not in this list (e.g. `frodokem`, `ntru`) are recognized by the
+/// NIST catalog but have no registered implementation yet.
+const KEM_PREFERENCE: &[&str] = &["kyber1024"];
+
+/// Signature suites this gateway can negotiate, in order of decreasing
+/// preference. Names not in this list (e.g. `falcon`, `sphincs+`) are
+/// recognized by the catalog but have no registered implementation yet.
+const SIG_PREFERENCE: &[&str] = &["dilithium5"];
+
+/// Picks the highest-preference suite that both the server's preference
+/// list and the client's offer contain.
+fn negotiate(preference: &[&'static str], offered: &[String]) -> Option<&'static str> {
+    preference
+        .iter()
+        .copied()
+        .find(|candidate| offered.iter().any(|o| o == candidate))
+}
+
+/// Where a `KnownKey`'s verification material comes from.
+enum KeySource {
+    /// A public key fixed when the actor was registered (e.g. onboarded out
+    /// of band). Unaffected by `/pqc/rotate`. This is how the admin actor
+    /// allowed to call `/pqc/rotate` is onboarded — see `admin_known_key` —
+    /// since the gateway's own suite keypairs never leave the process their
+    /// secret half belongs to.
+    Static(Vec<u8>),
+    /// The gateway's own active signature suite. Tracks `/pqc/rotate`
+    /// automatically, accepting the active key and, during its grace
+    /// window, the just-retired one too.
+    OwnSigSuite(&'static str),
+}
+
+/// A public key registered under a `keyId`, along with the algorithm it's
+/// expected to be used with. `proxy_request` resolves the `keyId` named in
+/// an incoming `Signature` header to one of these before verifying.
+struct KnownKey {
+    algorithm: SigAlgorithm,
+    source: KeySource,
+    // Whether this actor may call privileged endpoints like `/pqc/rotate`.
+    // A `session:<id>` keyId (anyone who's completed a handshake) is never
+    // privileged, regardless of this flag — see `verify_http_signature`.
+    admin: bool,
+}
+
+impl KnownKey {
+    /// Public keys a signature under this `keyId` is currently accepted
+    /// against — more than one while a rotation grace window is open.
+    fn candidate_public_keys(&self, data: &AppState) -> Vec<Vec<u8>> {
+        match &self.source {
+            KeySource::Static(pk) => vec![pk.clone()],
+            KeySource::OwnSigSuite(name) => {
+                let rotating = data
+                    .sigs
+                    .get(name)
+                    .expect("OwnSigSuite always names a registered suite")
+                    .lock()
+                    .unwrap();
+                let mut keys = vec![rotating.active.public_key()];
+                if let Some(retired) = rotating.valid_retired_public_key() {
+                    keys.push(retired.to_vec());
+                }
+                keys
+            }
+        }
+    }
+}
+
+/// Signature algorithms accepted in the `algorithm` parameter of the
+/// `Signature` header. New PQC signature schemes are added here as
+/// variants so `verify_http_signature` stays oblivious to the specifics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SigAlgorithm {
+    RsaSha256,
+    Dilithium5,
+}
+
+impl SigAlgorithm {
+    fn parse(raw: &str) -> Option<Self> {
+        match raw {
+            "rsa-sha256" => Some(SigAlgorithm::RsaSha256),
+            "dilithium5" => Some(SigAlgorithm::Dilithium5),
+            _ => None,
+        }
+    }
+
+    fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+        match self {
+            // No RSA keys are registered in this gateway; kept as a variant
+            // so legacy actors can be onboarded without another enum.
+            SigAlgorithm::RsaSha256 => false,
+            SigAlgorithm::Dilithium5 => {
+                let pk = match DilithiumPublicKey::from_bytes(public_key) {
+                    Ok(pk) => pk,
+                    Err(_) => return false,
+                };
+                let sig = match DilithiumSignature::from_bytes(signature) {
+                    Ok(sig) => sig,
+                    Err(_) => return false,
+                };
+                dilithium5::verify_detached_signature(&sig, message, &pk).is_ok()
+            }
+        }
+    }
+}
+
+/// Failure modes surfaced to callers of `proxy_request` and `pqc_rotate`.
+/// Signature failures map to `401 Unauthorized`; digest, body and rotation
+/// target errors to `400`, a non-admin keyId on an admin endpoint to `403`,
+/// and a keystore write failure to `500` (see `status_code`). The body names
+/// the specific reason so clients can tell a missing header apart from a bad
+/// signature.
+#[derive(Debug, Error)]
+enum MyError {
+    #[error("missing Signature header")]
+    MissingSignatureHeader,
+    #[error("malformed Signature header: {0}")]
+    MalformedSignatureHeader(String),
+    #[error("unknown keyId: {0}")]
+    UnknownKeyId(String),
+    #[error("signature is not valid base64")]
+    InvalidSignatureEncoding,
+    #[error("unsupported or mismatched algorithm: {0}")]
+    AlgorithmMismatch(String),
+    #[error("signature verification failed")]
+    InvalidSignature,
+    #[error("missing Digest header")]
+    MissingDigestHeader,
+    #[error("malformed Digest header: {0}")]
+    MalformedDigestHeader(String),
+    #[error("unsupported digest algorithm: {0}")]
+    UnsupportedDigestAlgorithm(String),
+    #[error("request body does not match Digest header")]
+    DigestMismatch,
+    #[error("invalid request body: {0}")]
+    InvalidRequestBody(String),
+    #[error("unknown rotation target: {0}")]
+    UnknownRotationTarget(String),
+    #[error("failed to persist rotated keypair: {0}")]
+    KeystorePersistFailed(String),
+    #[error("keyId is not authorized for this endpoint: {0}")]
+    NotAuthorized(String),
+}
+
+impl actix_web::ResponseError for MyError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            // The body itself is malformed or doesn't match what was
+            // promised, independent of who's signing it: 400, not 401.
+            MyError::MissingDigestHeader
+            | MyError::MalformedDigestHeader(_)
+            | MyError::UnsupportedDigestAlgorithm(_)
+            | MyError::DigestMismatch
+            | MyError::InvalidRequestBody(_)
+            | MyError::UnknownRotationTarget(_) => actix_web::http::StatusCode::BAD_REQUEST,
+            MyError::KeystorePersistFailed(_) => {
+                actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+            }
+            // The signature is valid — the keyId just isn't allowed to call
+            // this endpoint — so this is a 403, not a 401.
+            MyError::NotAuthorized(_) => actix_web::http::StatusCode::FORBIDDEN,
+            _ => actix_web::http::StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).body(self.to_string())
+    }
+}
+
+/// The parsed components of a `Signature` header, e.g.
+/// `keyId="server#main-key",algorithm="dilithium5",headers="(request-target) host date digest",signature="..."`.
+struct ParsedSignature {
+    key_id: String,
+    algorithm: String,
+    headers: Vec<String>,
+    signature: String,
+}
+
+fn parse_signature_header(raw: &str) -> Result<ParsedSignature, MyError> {
+    let mut key_id = None;
+    let mut algorithm = None;
+    let mut headers = None;
+    let mut signature = None;
+
+    for part in raw.split(',') {
+        let part = part.trim();
+        if part.is_empty() {
+            continue;
+        }
+        let (key, value) = part
+            .split_once('=')
+            .ok_or_else(|| MyError::MalformedSignatureHeader(part.to_string()))?;
+        let value = value.trim_matches('"');
+        match key {
+            "keyId" => key_id = Some(value.to_string()),
+            "algorithm" => algorithm = Some(value.to_string()),
+            "headers" => headers = Some(value.split(' ').map(str::to_string).collect()),
+            "signature" => signature = Some(value.to_string()),
+            // "created", "expires" and other extension params aren't used yet.
+            _ => {}
+        }
+    }
+
+    Ok(ParsedSignature {
+        key_id: key_id.ok_or(MyError::MalformedSignatureHeader("missing keyId".into()))?,
+        algorithm: algorithm
+            .ok_or(MyError::MalformedSignatureHeader("missing algorithm".into()))?,
+        headers: headers
+            .ok_or(MyError::MalformedSignatureHeader("missing headers".into()))?,
+        signature: signature
+            .ok_or(MyError::MalformedSignatureHeader("missing signature".into()))?,
+    })
+}
+
+/// Reconstructs the signing string by concatenating the listed
+/// pseudo-headers as `name: value` lines, in the order `headers` lists them.
+///
+/// `verified_digest` is the already-verified `Digest` header value (see
+/// `verify_body_digest`); when present it's substituted for the literal
+/// `digest` header, the same way `(request-target)` is synthesized rather
+/// than read verbatim — this is what folds the body hash into the signature,
+/// mirroring `x-amz-content-sha256` in SigV4.
+fn build_signing_string(
+    req: &HttpRequest,
+    headers: &[String],
+    verified_digest: Option<&str>,
+) -> Result<String, MyError> {
+    let mut lines = Vec::with_capacity(headers.len());
+    for name in headers {
+        let value = if name.eq_ignore_ascii_case("(request-target)") {
+            let path_and_query = req
+                .uri()
+                .path_and_query()
+                .map(|p| p.as_str())
+                .unwrap_or_else(|| req.uri().path());
+            format!("{} {}", req.method().as_str().to_lowercase(), path_and_query)
+        } else if name.eq_ignore_ascii_case("digest") {
+            verified_digest
+                .ok_or_else(|| {
+                    MyError::MalformedSignatureHeader(
+                        "digest listed in signature but request body was never verified".into(),
+                    )
+                })?
+                .to_string()
+        } else {
+            req.headers()
+                .get(name.as_str())
+                .and_then(|v| v.to_str().ok())
+                .ok_or_else(|| {
+                    MyError::MalformedSignatureHeader(format!(
+                        "request is missing header listed in signature: {name}"
+                    ))
+                })?
+                .to_string()
+        };
+        lines.push(format!("{name}: {value}"));
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Pseudo/real headers a `Signature` must cover for `verify_http_signature`
+/// to accept it: without `(request-target)` the signature says nothing
+/// about which request it's for, and without `digest` it says nothing
+/// about the body. A client is otherwise free to list any subset of
+/// `headers` it likes, so a signature covering only incidental headers
+/// would verify while leaving the URL or body free to tamper with.
+const REQUIRED_SIGNED_HEADERS: &[&str] = &["(request-target)", "digest"];
+
+fn verify_http_signature(
+    req: &HttpRequest,
+    verified_digest: Option<&str>,
+    data: &AppState,
+    require_admin: bool,
+) -> Result<(), MyError> {
+    let raw = req
+        .headers()
+        .get("Signature")
+        .ok_or(MyError::MissingSignatureHeader)?
+        .to_str()
+        .map_err(|_| MyError::MalformedSignatureHeader("Signature header is not UTF-8".into()))?;
+
+    let parsed = parse_signature_header(raw)?;
+
+    for required in REQUIRED_SIGNED_HEADERS {
+        if !parsed.headers.iter().any(|h| h.eq_ignore_ascii_case(required)) {
+            return Err(MyError::MalformedSignatureHeader(format!(
+                "signature must cover \"{required}\""
+            )));
+        }
+    }
+
+    let signature_bytes = BASE64
+        .decode(&parsed.signature)
+        .map_err(|_| MyError::InvalidSignatureEncoding)?;
+
+    let signing_string = build_signing_string(req, &parsed.headers, verified_digest)?;
+
+    // A `keyId` of `session:<id>` names a shared secret established by a
+    // prior `/pqc/handshake` rather than a registered actor key: the
+    // signature is an HMAC keyed by that secret instead of a public-key
+    // signature, so it's verified separately from `known_keys`. A completed
+    // handshake is self-provisioned by anyone, so it never satisfies
+    // `require_admin` — otherwise any anonymous caller could hand itself
+    // access to privileged endpoints like `/pqc/rotate` just by handshaking.
+    if let Some(session_id) = parsed.key_id.strip_prefix("session:") {
+        if require_admin {
+            return Err(MyError::NotAuthorized(parsed.key_id.clone()));
+        }
+        return verify_session_signature(
+            &data.sessions,
+            session_id,
+            &parsed.algorithm,
+            signing_string.as_bytes(),
+            &signature_bytes,
+        );
+    }
+
+    let known_key = data
+        .known_keys
+        .get(&parsed.key_id)
+        .ok_or_else(|| MyError::UnknownKeyId(parsed.key_id.clone()))?;
+
+    if require_admin && !known_key.admin {
+        return Err(MyError::NotAuthorized(parsed.key_id.clone()));
+    }
+
+    let algorithm = SigAlgorithm::parse(&parsed.algorithm)
+        .ok_or_else(|| MyError::AlgorithmMismatch(parsed.algorithm.clone()))?;
+    if algorithm != known_key.algorithm {
+        return Err(MyError::AlgorithmMismatch(parsed.algorithm));
+    }
+
+    let verifies = known_key
+        .candidate_public_keys(data)
+        .iter()
+        .any(|pk| algorithm.verify(pk, signing_string.as_bytes(), &signature_bytes));
+    if verifies {
+        Ok(())
+    } else {
+        Err(MyError::InvalidSignature)
+    }
+}
+
+/// The only signature algorithm accepted for a `session:<id>` `keyId`: an
+/// HMAC over the signing string, keyed by the shared secret `pqc_handshake`
+/// derived and stashed in `data.sessions`. This is what makes a completed
+/// handshake's shared secret actually useful to `proxy_request`, rather
+/// than a value that's written once and never read.
+const SESSION_SIGNATURE_ALGORITHM: &str = "hmac-sha256-session";
+
+fn verify_session_signature(
+    sessions: &Mutex<HashMap<SessionId, Session>>,
+    session_id: &str,
+    algorithm: &str,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), MyError> {
+    if algorithm != SESSION_SIGNATURE_ALGORITHM {
+        return Err(MyError::AlgorithmMismatch(algorithm.to_string()));
+    }
+
+    let sessions = sessions.lock().unwrap();
+    let session = sessions
+        .get(session_id)
+        .filter(|session| session.created_at.elapsed() < SESSION_TTL)
+        .ok_or_else(|| MyError::UnknownKeyId(format!("session:{session_id}")))?;
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(&session.shared_secret)
+        .expect("HMAC-SHA256 accepts a key of any length");
+    mac.update(message);
+    mac.verify_slice(signature)
+        .map_err(|_| MyError::InvalidSignature)
+}
+
+/// Parses a `Digest: SHA-256=<base64>` header, the only digest algorithm
+/// this gateway accepts.
+fn parse_digest_header(raw: &str) -> Result<Vec<u8>, MyError> {
+    let (algorithm, value) = raw
+        .split_once('=')
+        .ok_or_else(|| MyError::MalformedDigestHeader(raw.to_string()))?;
+    if !algorithm.eq_ignore_ascii_case("sha-256") {
+        return Err(MyError::UnsupportedDigestAlgorithm(algorithm.to_string()));
+    }
+    BASE64
+        .decode(value)
+        .map_err(|_| MyError::MalformedDigestHeader(raw.to_string()))
+}
+
+/// Hard ceiling on a proxied body. `Digest` can only be compared once every
+/// byte has arrived (see `verify_streamed_digest`), so this is the one
+/// failure a streaming verifier *can* catch early: an oversized upload is
+/// rejected as soon as it crosses the limit instead of being read to
+/// completion first.
+const MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+
+/// Verifies the body against `digest_header` as it streams in, folding each
+/// chunk into a running `Sha256` as soon as it arrives rather than buffering
+/// the whole body and hashing it in one shot afterwards — and rejecting
+/// immediately, without waiting for the rest, if the body crosses
+/// `MAX_BODY_BYTES`. `Digest` is always a flat `SHA-256(body)` regardless of
+/// `Transfer-Encoding`: actix's `web::Payload` already yields the same chunk
+/// stream whether the client sent `Content-Length` or `chunked` framing, so
+/// there's no separate "chunked" hash to verify against — a prior revision
+/// tried to verify a rolling hash chain here, which could never match the
+/// flat digest clients actually send and rejected every chunked request.
+/// Folding chunks into the hasher as they arrive is as far as "fail fast"
+/// can go for a flat digest, though: a SHA-256 can't be compared against
+/// `expected` until the final chunk has been hashed, no matter how it's
+/// computed, so a tampered body is only caught once the stream ends.
+///
+/// This function still returns the whole body as a `Vec<u8>`, so memory use
+/// is the same as buffering it up front (`MAX_BODY_BYTES` bounds that, same
+/// as before) — what changed is only *when* hashing happens relative to
+/// reading, not whether the body ends up collected. A true zero-buffering
+/// pass-through, where verified bytes are forwarded upstream as they arrive
+/// instead of being handed back as one `Vec`, would need `proxy_request` to
+/// stream its response from a real upstream connection opened alongside this
+/// verification; `proxy_request` doesn't make an upstream call at all yet
+/// (it returns a canned response — see its body), so there's no destination
+/// to stream verified chunks *to*. That's out of scope here: it's a
+/// proxying feature, not a digest-verification one.
+async fn verify_streamed_digest(
+    payload: &mut web::Payload,
+    digest_header: &str,
+) -> Result<Vec<u8>, MyError> {
+    let expected = parse_digest_header(digest_header)?;
+
+    let mut hasher = Sha256::new();
+    let mut body = Vec::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk =
+            chunk.map_err(|_| MyError::MalformedDigestHeader("failed to read request body".into()))?;
+        if body.len() + chunk.len() > MAX_BODY_BYTES {
+            return Err(MyError::InvalidRequestBody(format!(
+                "body exceeds {MAX_BODY_BYTES} byte limit"
+            )));
+        }
+        hasher.update(&chunk);
+        body.extend_from_slice(&chunk);
+    }
+
+    if hasher.finalize().as_slice() == expected.as_slice() {
+        Ok(body)
+    } else {
+        Err(MyError::DigestMismatch)
+    }
+}
+
+#[cfg(test)]
+fn digest_matches(expected: &[u8], body: &[u8]) -> bool {
+    Sha256::digest(body).as_slice() == expected
+}
+
+#[derive(Deserialize)]
+struct HandshakeRequest {
+    // KEM suites the client supports, in the client's own preference order.
+    kem: Vec<String>,
+    // Signature suites the client supports, in the client's own preference order.
+    sig: Vec<String>,
+    // Base64-encoded public key for the client's negotiated KEM suite.
+    client_kem_pk: String,
+}
+
+#[derive(Serialize)]
+struct HandshakeResponse {
+    // Identifies the shared secret for `proxy_request`'s `Signature` header:
+    // sign requests with `keyId="session:<session_id>"`,
+    // `algorithm="hmac-sha256-session"`, HMAC-SHA256 keyed by the shared
+    // secret recovered from `ciphertext`.
+    session_id: String,
+    // The suites the server picked, echoed back so the client knows what
+    // was negotiated.
+    kem: String,
+    sig: String,
+    ciphertext: String,
+    signature: String,
+    server_sig_pk: String,
+}
+
+fn prune_expired_sessions(sessions: &mut HashMap<SessionId, Session>) {
+    sessions.retain(|_, session| session.created_at.elapsed() < SESSION_TTL);
+}
+
+async fn pqc_handshake(
+    body: web::Json<HandshakeRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    // 1. Client advertises the KEM/signature suites it supports.
+    // 2. Server picks the strongest suite it also supports for each.
+    // 3. Server encapsulates a shared secret under the client's KEM public key
+    //    and signs the ciphertext with the negotiated signature suite.
+    // 4. Server returns (kem, sig, ciphertext, signature, server_sig_pk), and
+    //    stashes the shared secret under a session id. The client decapsulates
+    //    the same shared secret locally and authenticates later calls to
+    //    proxy_request with keyId="session:<session_id>" (see
+    //    verify_session_signature), instead of a registered actor key.
+    //    pqc_rotate does not accept session keyIds; it needs an admin key.
+
+    let kem_name = match negotiate(KEM_PREFERENCE, &body.kem) {
+        Some(name) => name,
+        None => return HttpResponse::BadRequest().body("no mutually supported KEM suite"),
+    };
+    let sig_name = match negotiate(SIG_PREFERENCE, &body.sig) {
+        Some(name) => name,
+        None => return HttpResponse::BadRequest().body("no mutually supported signature suite"),
+    };
+
+    // Negotiated names always come from our own preference lists, which are
+    // kept in sync with the registries populated in `main`.
+    let kem = data.kems.get(kem_name).expect("negotiated KEM suite is registered").lock().unwrap();
+    let sig = data.sigs.get(sig_name).expect("negotiated signature suite is registered").lock().unwrap();
+
+    let client_pk_bytes = match BASE64.decode(&body.client_kem_pk) {
+        Ok(bytes) => bytes,
+        Err(_) => return HttpResponse::BadRequest().body("client_kem_pk is not valid base64"),
+    };
+    let (shared_secret, ciphertext) = match kem.active.encapsulate(&client_pk_bytes) {
+        Ok(pair) => pair,
+        Err(_) => {
+            return HttpResponse::BadRequest().body("client_kem_pk is not a valid public key for the negotiated KEM")
+        }
+    };
+    let signature = sig.active.sign(&ciphertext);
+
+    let session_id = Uuid::new_v4().to_string();
+    {
+        let mut sessions = data.sessions.lock().unwrap();
+        prune_expired_sessions(&mut sessions);
+        sessions.insert(
+            session_id.clone(),
+            Session {
+                shared_secret,
+                created_at: Instant::now(),
+            },
+        );
+    }
+
+    HttpResponse::Ok().json(HandshakeResponse {
+        session_id,
+        kem: kem_name.to_string(),
+        sig: sig_name.to_string(),
+        ciphertext: BASE64.encode(&ciphertext),
+        signature: BASE64.encode(&signature),
+        server_sig_pk: BASE64.encode(sig.active.public_key()),
+    })
+}
+
+/// Upstream service a request is forwarded to, chosen by path prefix.
+#[derive(Debug, Clone, Copy)]
+enum Upstream {
+    PostgREST,
+    GoTrue,
+}
+
+impl Upstream {
+    fn for_path(path: &str) -> Self {
+        if path.starts_with("/auth/") {
+            Upstream::GoTrue
+        } else {
+            Upstream::PostgREST
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Upstream::PostgREST => "PostgREST",
+            Upstream::GoTrue => "GoTrue",
+        }
+    }
+}
+
+/// Verifies the Digest and HTTP Signature on an incoming request, returning
+/// its verified body. Shared by `proxy_request` and `pqc_rotate`, the two
+/// endpoints that require a caller to authenticate as a known actor.
+///
+/// `require_admin` gates privileged endpoints (`pqc_rotate`): it rejects a
+/// `session:<id>` keyId outright and requires a registered `known_keys`
+/// entry to be marked `admin`, so completing an (unauthenticated) handshake
+/// is never enough on its own to reach them.
+async fn authenticate_request(
+    req: &HttpRequest,
+    payload: &mut web::Payload,
+    data: &AppState,
+    require_admin: bool,
+) -> Result<Vec<u8>, MyError> {
+    let digest_header = req
+        .headers()
+        .get("Digest")
+        .ok_or(MyError::MissingDigestHeader)?
+        .to_str()
+        .map_err(|_| MyError::MalformedDigestHeader("Digest header is not UTF-8".into()))?
+        .to_string();
+
+    let body = verify_streamed_digest(payload, &digest_header).await?;
+
+    // Fold the already-verified digest into the signing string so a valid
+    // signature authenticates the body, not just the listed headers.
+    verify_http_signature(req, Some(&digest_header), data, require_admin)?;
+
+    Ok(body)
+}
+
+async fn proxy_request(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    mut payload: web::Payload,
+) -> Result<HttpResponse, MyError> {
+    let upstream = Upstream::for_path(req.path());
+    let body = authenticate_request(&req, &mut payload, &data, false).await?;
+
+    println!(
+        "Digest and HTTP Signature verified. Proxying {} bytes to {}...",
+        body.len(),
+        upstream.name()
+    );
+    Ok(HttpResponse::Ok().body(format!("Proxy Successful ({})", upstream.name())))
+}
+
+#[derive(Deserialize)]
+struct RotateRequest {
+    // Which registry to rotate the named suite in: "kem" or "sig".
+    kind: String,
+    // Catalog name of the suite to rotate, e.g. "kyber1024" or "dilithium5".
+    algorithm: String,
+}
+
+#[derive(Serialize)]
+struct RotateResponse {
+    kind: String,
+    algorithm: String,
+    // Base64-encoded public key of the freshly installed keypair.
+    public_key: String,
+}
+
+fn rotate_kem(data: &AppState, algorithm: &str) -> Result<Vec<u8>, MyError> {
+    let slot = data
+        .kems
+        .get(algorithm)
+        .ok_or_else(|| MyError::UnknownRotationTarget(format!("kem {algorithm}")))?;
+    let new_kem = generate_kem(algorithm)
+        .ok_or_else(|| MyError::UnknownRotationTarget(format!("kem {algorithm}")))?;
+    let new_public_key = new_kem.public_key();
+    data.keystore
+        .store(algorithm, &new_public_key, &new_kem.secret_key())
+        .map_err(|e| MyError::KeystorePersistFailed(e.to_string()))?;
+
+    let mut rotating = slot.lock().unwrap();
+    let retiring_public_key = rotating.active.public_key();
+    rotating.rotate(new_kem, retiring_public_key);
+    Ok(new_public_key)
+}
+
+fn rotate_sig(data: &AppState, algorithm: &str) -> Result<Vec<u8>, MyError> {
+    let slot = data
+        .sigs
+        .get(algorithm)
+        .ok_or_else(|| MyError::UnknownRotationTarget(format!("sig {algorithm}")))?;
+    let new_sig = generate_sig(algorithm)
+        .ok_or_else(|| MyError::UnknownRotationTarget(format!("sig {algorithm}")))?;
+    let new_public_key = new_sig.public_key();
+    data.keystore
+        .store(algorithm, &new_public_key, &new_sig.secret_key())
+        .map_err(|e| MyError::KeystorePersistFailed(e.to_string()))?;
+
+    let mut rotating = slot.lock().unwrap();
+    let retiring_public_key = rotating.active.public_key();
+    rotating.rotate(new_sig, retiring_public_key);
+    Ok(new_public_key)
+}
+
+/// Generates a fresh keypair for a registered KEM or signature suite,
+/// persists it to the keystore, and installs it as the active key —
+/// keeping the outgoing key valid for `ROTATION_GRACE_PERIOD` so signatures
+/// and handshakes already in flight keep verifying. Requires the same
+/// Digest + HTTP Signature authentication as `proxy_request`, but further
+/// restricted to a `known_keys` entry registered as `admin`: unlike
+/// `proxy_request`, a `session:<id>` keyId from a self-provisioned
+/// handshake is never enough to rotate the gateway's live keys.
+async fn pqc_rotate(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    mut payload: web::Payload,
+) -> Result<HttpResponse, MyError> {
+    let body = authenticate_request(&req, &mut payload, &data, true).await?;
+    let request: RotateRequest = serde_json::from_slice(&body)
+        .map_err(|e| MyError::InvalidRequestBody(e.to_string()))?;
+
+    let new_public_key = match request.kind.as_str() {
+        "kem" => rotate_kem(&data, &request.algorithm)?,
+        "sig" => rotate_sig(&data, &request.algorithm)?,
+        other => {
+            return Err(MyError::UnknownRotationTarget(format!(
+                "{other} (expected \"kem\" or \"sig\")"
+            )))
+        }
+    };
+
+    println!("Rotated {} suite {}", request.kind, request.algorithm);
+
+    Ok(HttpResponse::Ok().json(RotateResponse {
+        kind: request.kind,
+        algorithm: request.algorithm,
+        public_key: BASE64.encode(new_public_key),
+    }))
+}
+
+#[derive(Serialize)]
+struct KeyEntry {
+    algorithm: String,
+    // Base64-encoded public key.
+    public_key: String,
+    // True if this is a retired key still inside its post-rotation grace
+    // window rather than the currently active one.
+    retired: bool,
+}
+
+#[derive(Serialize)]
+struct KeysResponse {
+    kem: Vec<KeyEntry>,
+    sig: Vec<KeyEntry>,
+}
+
+/// Discovery endpoint for verification material, analogous to how
+/// ActivityPub actors publish their `publicKey`: lists each suite's active
+/// public key, plus any still-valid retired key so a caller mid-rotation
+/// doesn't have to guess which one signed its last handshake.
+async fn pqc_keys(data: web::Data<AppState>) -> impl Responder {
+    fn kem_entries(kems: &HashMap<&'static str, Mutex<Rotating<Box<dyn Kem>>>>) -> Vec<KeyEntry> {
+        kems.iter()
+            .flat_map(|(name, slot)| {
+                let rotating = slot.lock().unwrap();
+                let mut entries = vec![KeyEntry {
+                    algorithm: name.to_string(),
+                    public_key: BASE64.encode(rotating.active.public_key()),
+                    retired: false,
+                }];
+                if let Some(pk) = rotating.valid_retired_public_key() {
+                    entries.push(KeyEntry {
+                        algorithm: name.to_string(),
+                        public_key: BASE64.encode(pk),
+                        retired: true,
+                    });
+                }
+                entries
+            })
+            .collect()
+    }
+
+    fn sig_entries(sigs: &HashMap<&'static str, Mutex<Rotating<Box<dyn Sign>>>>) -> Vec<KeyEntry> {
+        sigs.iter()
+            .flat_map(|(name, slot)| {
+                let rotating = slot.lock().unwrap();
+                let mut entries = vec![KeyEntry {
+                    algorithm: name.to_string(),
+                    public_key: BASE64.encode(rotating.active.public_key()),
+                    retired: false,
+                }];
+                if let Some(pk) = rotating.valid_retired_public_key() {
+                    entries.push(KeyEntry {
+                        algorithm: name.to_string(),
+                        public_key: BASE64.encode(pk),
+                        retired: true,
+                    });
+                }
+                entries
+            })
+            .collect()
+    }
+
+    HttpResponse::Ok().json(KeysResponse {
+        kem: kem_entries(&data.kems),
+        sig: sig_entries(&data.sigs),
+    })
+}
+
+/// `keyId` of the admin actor allowed to call `/pqc/rotate`.
+const ADMIN_KEY_ID: &str = "admin#rotate-key";
+
+/// Builds the `known_keys` entry for the admin actor allowed to call
+/// `/pqc/rotate`, from a base64-encoded Dilithium5 public key in
+/// `PQC_ADMIN_PUBLIC_KEY`. Returns `None` if the variable isn't set.
+///
+/// This is required for `/pqc/rotate` to be reachable at all: the
+/// gateway's own suite keypairs (`KeySource::OwnSigSuite`) never give out
+/// their secret key, so without an out-of-band admin key registered here,
+/// nothing could ever produce a signature `require_admin` accepts.
+fn admin_known_key() -> std::io::Result<Option<(String, KnownKey)>> {
+    let raw = match env::var("PQC_ADMIN_PUBLIC_KEY") {
+        Ok(raw) => raw,
+        Err(_) => return Ok(None),
+    };
+    let public_key = BASE64.decode(raw.trim()).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("PQC_ADMIN_PUBLIC_KEY is not valid base64: {e}"),
+        )
+    })?;
+    Ok(Some((
+        ADMIN_KEY_ID.to_string(),
+        KnownKey {
+            algorithm: SigAlgorithm::Dilithium5,
+            source: KeySource::Static(public_key),
+            admin: true,
+        },
+    )))
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     println!("Starting Project EventHorizon PQC Gateway...");
-    
-    // Generate Server Keys (Kyber1024)
-    // let (pk, sk) = keypair();
-    let pk = vec![0; 1024]; 
-    let sk = vec![0; 1024];
+
+    let keystore_dir = env::var("PQC_KEYSTORE_DIR").unwrap_or_else(|_| "pqc-keystore".to_string());
+    let keystore = keystore::Keystore::open(keystore_dir)?;
+
+    let mut kems: HashMap<&'static str, Mutex<Rotating<Box<dyn Kem>>>> = HashMap::new();
+    for &algorithm in KEM_PREFERENCE {
+        let kem = load_or_generate_kem(&keystore, algorithm)?;
+        kems.insert(algorithm, Mutex::new(Rotating::new(kem)));
+    }
+
+    let mut sigs: HashMap<&'static str, Mutex<Rotating<Box<dyn Sign>>>> = HashMap::new();
+    for &algorithm in SIG_PREFERENCE {
+        let sig = load_or_generate_sig(&keystore, algorithm)?;
+        sigs.insert(algorithm, Mutex::new(Rotating::new(sig)));
+    }
+
+    let mut known_keys = HashMap::new();
+    known_keys.insert(
+        "gateway#main-key".to_string(),
+        KnownKey {
+            algorithm: SigAlgorithm::Dilithium5,
+            source: KeySource::OwnSigSuite("dilithium5"),
+            admin: false,
+        },
+    );
+    match admin_known_key()? {
+        Some((key_id, known_key)) => {
+            known_keys.insert(key_id, known_key);
+        }
+        None => println!(
+            "PQC_ADMIN_PUBLIC_KEY not set; no keyId can call /pqc/rotate until one is registered"
+        ),
+    }
+
+    let state = web::Data::new(AppState {
+        kems,
+        sigs,
+        sessions: Mutex::new(HashMap::new()),
+        known_keys,
+        keystore,
+    });
 
     HttpServer::new(move || {
         App::new()
-            .data(AppState {
-                server_public_key: pk.clone(),
-                server_secret_key: sk.clone(),
-            })
+            .app_data(state.clone())
             .route("/pqc/handshake", web::post().to(pqc_handshake))
-            .route("/{tail:.*}", web::any().to(proxy_request))
+            .route("/pqc/rotate", web::post().to(pqc_rotate))
+            .route("/pqc/keys", web::get().to(pqc_keys))
+            .route("/{tail:.*}", web::route().to(proxy_request))
     })
     .bind("0.0.0.0:8080")?
     .run()
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_digest_header_decodes_sha256() {
+        let digest = Sha256::digest(b"hello world");
+        let header = format!("SHA-256={}", BASE64.encode(digest));
+        assert_eq!(parse_digest_header(&header).unwrap(), digest.to_vec());
+    }
+
+    #[test]
+    fn parse_digest_header_rejects_other_algorithms() {
+        assert!(matches!(
+            parse_digest_header("MD5=deadbeef"),
+            Err(MyError::UnsupportedDigestAlgorithm(_))
+        ));
+    }
+
+    #[test]
+    fn digest_matches_detects_mismatch() {
+        let body = b"hello world";
+        let expected = Sha256::digest(body).to_vec();
+        assert!(digest_matches(&expected, body));
+        assert!(!digest_matches(&expected, b"tampered body"));
+    }
+
+    #[test]
+    fn digest_matches_accepts_empty_body() {
+        // Regression test: a rolling hash chain over chunks (seeded at all
+        // zeros) never equals a flat SHA-256 of the same bytes, even for an
+        // empty body. `digest_matches` must use the same flat hash clients
+        // compute for the `Digest` header regardless of body size.
+        let expected = Sha256::digest(b"").to_vec();
+        assert!(digest_matches(&expected, b""));
+    }
+
+    fn sessions_with(shared_secret: &[u8]) -> Mutex<HashMap<SessionId, Session>> {
+        let mut sessions = HashMap::new();
+        sessions.insert(
+            "s1".to_string(),
+            Session {
+                shared_secret: shared_secret.to_vec(),
+                created_at: Instant::now(),
+            },
+        );
+        Mutex::new(sessions)
+    }
+
+    #[test]
+    fn verify_session_signature_accepts_matching_hmac() {
+        let sessions = sessions_with(b"handshake-derived-secret");
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"handshake-derived-secret").unwrap();
+        mac.update(b"the signing string");
+        let signature = mac.finalize().into_bytes();
+
+        assert!(verify_session_signature(
+            &sessions,
+            "s1",
+            SESSION_SIGNATURE_ALGORITHM,
+            b"the signing string",
+            &signature,
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn verify_session_signature_rejects_wrong_secret() {
+        let sessions = sessions_with(b"handshake-derived-secret");
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"a different secret").unwrap();
+        mac.update(b"the signing string");
+        let signature = mac.finalize().into_bytes();
+
+        assert!(matches!(
+            verify_session_signature(
+                &sessions,
+                "s1",
+                SESSION_SIGNATURE_ALGORITHM,
+                b"the signing string",
+                &signature,
+            ),
+            Err(MyError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn verify_session_signature_rejects_unknown_session() {
+        let sessions = sessions_with(b"handshake-derived-secret");
+        assert!(matches!(
+            verify_session_signature(
+                &sessions,
+                "no-such-session",
+                SESSION_SIGNATURE_ALGORITHM,
+                b"the signing string",
+                b"whatever",
+            ),
+            Err(MyError::UnknownKeyId(_))
+        ));
+    }
+
+    #[test]
+    fn verify_session_signature_rejects_wrong_algorithm() {
+        let sessions = sessions_with(b"handshake-derived-secret");
+        assert!(matches!(
+            verify_session_signature(&sessions, "s1", "rsa-sha256", b"msg", b"sig"),
+            Err(MyError::AlgorithmMismatch(_))
+        ));
+    }
+
+    /// A fresh on-disk keystore directory, unique per call so parallel tests
+    /// don't clobber each other's PEM files.
+    fn test_keystore() -> keystore::Keystore {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("pqc-auth-middleware-test-{}-{id}", std::process::id()));
+        keystore::Keystore::open(dir).expect("failed to open test keystore")
+    }
+
+    fn known_keys_with(key_id: &str, known_key: KnownKey) -> HashMap<String, KnownKey> {
+        let mut known_keys = HashMap::new();
+        known_keys.insert(key_id.to_string(), known_key);
+        known_keys
+    }
+
+    /// Builds the `Signature` header value `verify_http_signature` expects,
+    /// signing `signing_string` with `secret_key`.
+    fn dilithium_signature_header(
+        key_id: &str,
+        signing_string: &str,
+        secret_key: &DilithiumSecretKey,
+    ) -> String {
+        let signature = dilithium5::detached_sign(signing_string.as_bytes(), secret_key);
+        format!(
+            "keyId=\"{key_id}\",algorithm=\"dilithium5\",headers=\"(request-target) digest\",signature=\"{}\"",
+            BASE64.encode(signature.as_bytes())
+        )
+    }
+
+    #[test]
+    fn verify_http_signature_accepts_valid_dilithium_signature() {
+        let (public_key, secret_key) = dilithium5::keypair();
+        let signing_string = "(request-target): post /auth/test\ndigest: the-verified-digest";
+        let signature_header = dilithium_signature_header("actor#1", signing_string, &secret_key);
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/auth/test")
+            .insert_header(("Signature", signature_header))
+            .to_http_request();
+
+        let known_keys = known_keys_with(
+            "actor#1",
+            KnownKey {
+                algorithm: SigAlgorithm::Dilithium5,
+                source: KeySource::Static(public_key.as_bytes().to_vec()),
+                admin: false,
+            },
+        );
+        let data = AppState {
+            kems: HashMap::new(),
+            sigs: HashMap::new(),
+            sessions: Mutex::new(HashMap::new()),
+            known_keys,
+            keystore: test_keystore(),
+        };
+
+        assert!(verify_http_signature(&req, Some("the-verified-digest"), &data, false).is_ok());
+    }
+
+    #[test]
+    fn verify_http_signature_rejects_tampered_signature() {
+        let (public_key, secret_key) = dilithium5::keypair();
+        let signing_string = "(request-target): post /auth/test\ndigest: the-verified-digest";
+        let mut signature_header = dilithium_signature_header("actor#1", signing_string, &secret_key);
+        signature_header = signature_header.replace("signature=\"", "signature=\"AA");
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/auth/test")
+            .insert_header(("Signature", signature_header))
+            .to_http_request();
+
+        let known_keys = known_keys_with(
+            "actor#1",
+            KnownKey {
+                algorithm: SigAlgorithm::Dilithium5,
+                source: KeySource::Static(public_key.as_bytes().to_vec()),
+                admin: false,
+            },
+        );
+        let data = AppState {
+            kems: HashMap::new(),
+            sigs: HashMap::new(),
+            sessions: Mutex::new(HashMap::new()),
+            known_keys,
+            keystore: test_keystore(),
+        };
+
+        assert!(matches!(
+            verify_http_signature(&req, Some("the-verified-digest"), &data, false),
+            Err(MyError::InvalidSignature) | Err(MyError::InvalidSignatureEncoding)
+        ));
+    }
+
+    #[test]
+    fn verify_http_signature_rejects_unknown_key_id() {
+        let (_public_key, secret_key) = dilithium5::keypair();
+        let signing_string = "(request-target): post /auth/test\ndigest: the-verified-digest";
+        let signature_header = dilithium_signature_header("actor#nobody", signing_string, &secret_key);
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/auth/test")
+            .insert_header(("Signature", signature_header))
+            .to_http_request();
+
+        let data = AppState {
+            kems: HashMap::new(),
+            sigs: HashMap::new(),
+            sessions: Mutex::new(HashMap::new()),
+            known_keys: HashMap::new(),
+            keystore: test_keystore(),
+        };
+
+        assert!(matches!(
+            verify_http_signature(&req, Some("the-verified-digest"), &data, false),
+            Err(MyError::UnknownKeyId(_))
+        ));
+    }
+
+    #[test]
+    fn verify_http_signature_rejects_signature_missing_required_header() {
+        let (public_key, secret_key) = dilithium5::keypair();
+        let signing_string = "date: Tue, 01 Jan 2030 00:00:00 GMT";
+        let signature = dilithium5::detached_sign(signing_string.as_bytes(), &secret_key);
+        // Only lists "date" — neither "(request-target)" nor "digest" is
+        // covered, so nothing ties this signature to this request or body.
+        let signature_header = format!(
+            "keyId=\"actor#1\",algorithm=\"dilithium5\",headers=\"date\",signature=\"{}\"",
+            BASE64.encode(signature.as_bytes())
+        );
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/auth/test")
+            .insert_header(("Signature", signature_header))
+            .insert_header(("date", "Tue, 01 Jan 2030 00:00:00 GMT"))
+            .to_http_request();
+
+        let known_keys = known_keys_with(
+            "actor#1",
+            KnownKey {
+                algorithm: SigAlgorithm::Dilithium5,
+                source: KeySource::Static(public_key.as_bytes().to_vec()),
+                admin: false,
+            },
+        );
+        let data = AppState {
+            kems: HashMap::new(),
+            sigs: HashMap::new(),
+            sessions: Mutex::new(HashMap::new()),
+            known_keys,
+            keystore: test_keystore(),
+        };
+
+        assert!(matches!(
+            verify_http_signature(&req, Some("the-verified-digest"), &data, false),
+            Err(MyError::MalformedSignatureHeader(_))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn pqc_rotate_succeeds_for_admin_registered_key() {
+        let (admin_pk, admin_sk) = dilithium5::keypair();
+
+        let mut sigs: HashMap<&'static str, Mutex<Rotating<Box<dyn Sign>>>> = HashMap::new();
+        sigs.insert("dilithium5", Mutex::new(Rotating::new(Box::new(Dilithium5Sign::generate()))));
+
+        let known_keys = known_keys_with(
+            ADMIN_KEY_ID,
+            KnownKey {
+                algorithm: SigAlgorithm::Dilithium5,
+                source: KeySource::Static(admin_pk.as_bytes().to_vec()),
+                admin: true,
+            },
+        );
+        let data = web::Data::new(AppState {
+            kems: HashMap::new(),
+            sigs,
+            sessions: Mutex::new(HashMap::new()),
+            known_keys,
+            keystore: test_keystore(),
+        });
+
+        let body = serde_json::to_vec(&serde_json::json!({"kind": "sig", "algorithm": "dilithium5"}))
+            .unwrap();
+        let digest_header = format!("SHA-256={}", BASE64.encode(Sha256::digest(&body)));
+        let signing_string = format!("(request-target): post /pqc/rotate\ndigest: {digest_header}");
+        let signature_header = dilithium_signature_header(ADMIN_KEY_ID, &signing_string, &admin_sk);
+
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/pqc/rotate", web::post().to(pqc_rotate)),
+        )
+        .await;
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/pqc/rotate")
+            .insert_header(("Digest", digest_header))
+            .insert_header(("Signature", signature_header))
+            .set_payload(body)
+            .to_request();
+
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert!(
+            resp.status().is_success(),
+            "an admin-signed rotate request should succeed, got {}",
+            resp.status()
+        );
+    }
+}